@@ -0,0 +1,311 @@
+//! Chunk-level codec benchmarks.
+//!
+//! Run with `cargo bench --bench compression`. Throughput is reported per input byte.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+#[allow(dead_code)]
+#[path = "../compression_system.rs"]
+mod encs;
+
+use encs::{CompressionAlgorithm, CompressionEngine};
+
+const INPUT_SIZE: usize = 1024 * 1024; // 1MB
+const LARGE_INPUT_SIZE: usize = 256 * 1024 * 1024; // 256MB
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+const HUGE_INPUT_SIZE: usize = 1024 * 1024 * 1024; // 1GB
+#[cfg(feature = "tabular")]
+const TABLE_ROWS: u32 = 200_000;
+
+fn algorithms() -> Vec<CompressionAlgorithm> {
+    vec![
+        CompressionAlgorithm::Store,
+        CompressionAlgorithm::Zstd { level: 3 },
+        CompressionAlgorithm::Lz4 { level: None },
+        CompressionAlgorithm::Snappy,
+        CompressionAlgorithm::Brotli { quality: 6 },
+        CompressionAlgorithm::Deflate { level: 6 },
+    ]
+}
+
+fn random_bytes() -> Vec<u8> {
+    // xorshift keeps the input deterministic between runs
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..INPUT_SIZE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+fn repetitive_text() -> Vec<u8> {
+    b"The quick brown fox jumps over the lazy dog. "
+        .iter()
+        .copied()
+        .cycle()
+        .take(INPUT_SIZE)
+        .collect()
+}
+
+// Stand-in for a database dump: a table of INSERTs written out again after ~9MB of other
+// data, as repeated snapshots would be. The copies sit ~10MB apart, past the 8MB window
+// zstd uses up to level 19 but within one 16MB chunk.
+fn database_dump() -> Vec<u8> {
+    let table: Vec<u8> = (0..20_000u32)
+        .flat_map(|i| {
+            format!(
+                "INSERT INTO orders VALUES ({}, 'customer-{}', {}.{:02}, '2024-{:02}-{:02}');\n",
+                i, i.wrapping_mul(2_654_435_761) % 50_000, i * 37 % 10_000, i % 100, i % 12 + 1, i % 28 + 1
+            )
+            .into_bytes()
+        })
+        .collect();
+    let mut dump = table.clone();
+    for _ in 0..9 {
+        dump.extend(random_bytes().into_iter().map(|b| b'a' + b % 26));
+    }
+    dump.extend_from_slice(&table);
+    dump
+}
+
+fn json_fixture() -> Vec<u8> {
+    include_bytes!("data/events.json").to_vec()
+}
+
+// A slowly drifting sensor reading with a daily cycle, as little-endian f32
+fn float_series() -> Vec<u8> {
+    (0..INPUT_SIZE as u32 / 4)
+        .flat_map(|i| {
+            let t = i as f32 / 3600.0;
+            (21.5 + 0.01 * t + 3.0 * (t / 24.0 * std::f32::consts::TAU).sin()).to_le_bytes()
+        })
+        .collect()
+}
+
+#[cfg(feature = "tabular")]
+fn csv_table() -> Vec<u8> {
+    let mut table = Vec::new();
+    for i in 0..TABLE_ROWS {
+        table.extend_from_slice(format!(
+            "{},2024-{:02}-{:02},\"Customer {}, Ltd\",{}.{:02},{}\n",
+            i, i % 12 + 1, i % 28 + 1, i % 997, i * 7919 % 100_000, i % 100,
+            ["open", "closed", "pending"][i as usize % 3]
+        ).as_bytes());
+    }
+    table
+}
+
+fn bench_codecs(c: &mut Criterion) {
+    let engine = CompressionEngine::new().expect("engine");
+    let inputs = [
+        ("random", random_bytes()),
+        ("text", repetitive_text()),
+        ("json", json_fixture()),
+    ];
+    
+    for (data_name, data) in &inputs {
+        let mut group = c.benchmark_group(format!("compress/{}", data_name));
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        for algorithm in algorithms() {
+            group.bench_with_input(BenchmarkId::from_parameter(algorithm.name()), data, |b, data| {
+                b.iter(|| engine.compress_chunk(data, &algorithm, 0).expect("compress"));
+            });
+        }
+        group.finish();
+        
+        let mut group = c.benchmark_group(format!("decompress/{}", data_name));
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        for algorithm in algorithms() {
+            let compressed = engine.compress_chunk(data, &algorithm, 0).expect("compress");
+            group.bench_with_input(BenchmarkId::from_parameter(algorithm.name()), &compressed, |b, compressed| {
+                b.iter(|| engine.decompress_chunk(compressed, &algorithm).expect("decompress"));
+            });
+        }
+        group.finish();
+    }
+}
+
+fn bench_zstd_long_range(c: &mut Criterion) {
+    let engine = CompressionEngine::new().expect("engine");
+    let dump = database_dump();
+    
+    let mut group = c.benchmark_group("compress/dump");
+    group.throughput(Throughput::Bytes(dump.len() as u64));
+    group.sample_size(10);
+    for level in [19, 20, 22] {
+        let algorithm = CompressionAlgorithm::Zstd { level };
+        let compressed = engine.compress_chunk(&dump, &algorithm, 0).expect("compress");
+        println!(
+            "dump/zstd-{}: {} -> {} bytes (ratio {:.3})",
+            level,
+            dump.len(),
+            compressed.len(),
+            dump.len() as f64 / compressed.len() as f64
+        );
+        
+        group.bench_with_input(BenchmarkId::new("zstd", level), &dump, |b, dump| {
+            b.iter(|| engine.compress_chunk(dump, &algorithm, 0).expect("compress"));
+        });
+    }
+    group.finish();
+}
+
+// Whole-file compression of 256MB of log text on 8 threads. With a 1GB memory limit the file
+// is read whole and compressed on rayon; at 256MB it is over half the limit, so it streams
+// in batches of 8 chunks instead.
+fn bench_parallel_chunks(c: &mut Criterion) {
+    use encs::{CompressionOptions, EngineConfig};
+    
+    let dir = tempfile::TempDir::new().expect("temp dir");
+    let input = dir.path().join("large.log");
+    let output = dir.path().join("large.encs");
+    let text: Vec<u8> = (0u32..)
+        .flat_map(|i| format!("{} GET /api/items/{} 200 {}ms\n", i, i.wrapping_mul(2_654_435_761) % 100_000, i % 997).into_bytes())
+        .take(LARGE_INPUT_SIZE)
+        .collect();
+    std::fs::write(&input, &text).expect("write input");
+    
+    let options = CompressionOptions::builder().algorithm(CompressionAlgorithm::Zstd { level: 3 }).build();
+    let mut group = c.benchmark_group("compress_file/256mb");
+    group.throughput(Throughput::Bytes(text.len() as u64));
+    group.sample_size(10);
+    for (mode_name, memory_limit) in [("rayon", 1024 * 1024 * 1024), ("streaming", 256 * 1024 * 1024)] {
+        let config = EngineConfig { max_threads: 8, memory_limit, ..EngineConfig::default() };
+        let engine = CompressionEngine::with_config(config).expect("engine");
+        group.bench_function(mode_name, |b| {
+            b.iter(|| engine.compress_file(&input, &output, options.clone()).expect("compress"));
+        });
+    }
+    group.finish();
+}
+
+// Whole-file compression of 1GB through io_uring and through tokio's file I/O. Point
+// ENCS_BENCH_DIR at the drive to measure (an NVMe mount, say); the default temp dir is often
+// tmpfs, where neither waits on a device.
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+fn bench_io_uring(c: &mut Criterion) {
+    use encs::{CompressionOptions, EngineConfig};
+    
+    let dir = match std::env::var_os("ENCS_BENCH_DIR") {
+        Some(base) => tempfile::TempDir::new_in(base),
+        None => tempfile::TempDir::new(),
+    }.expect("temp dir");
+    let input = dir.path().join("huge.log");
+    let output = dir.path().join("huge.encs");
+    let line_source: Vec<u8> = (0u32..)
+        .flat_map(|i| format!("{} GET /api/items/{} 200 {}ms\n", i, i.wrapping_mul(2_654_435_761) % 100_000, i % 997).into_bytes())
+        .take(LARGE_INPUT_SIZE)
+        .collect();
+    {
+        use std::io::Write;
+        let mut file = std::fs::File::create(&input).expect("create input");
+        for _ in 0..HUGE_INPUT_SIZE / LARGE_INPUT_SIZE {
+            file.write_all(&line_source).expect("write input");
+        }
+    }
+    
+    let options = CompressionOptions::builder().algorithm(CompressionAlgorithm::Lz4 { level: None }).build();
+    let mut group = c.benchmark_group("compress_file/1gb");
+    group.throughput(Throughput::Bytes(HUGE_INPUT_SIZE as u64));
+    group.sample_size(10);
+    for (io_name, io_uring) in [("io_uring", true), ("tokio", false)] {
+        let engine = CompressionEngine::with_config(EngineConfig::builder().io_uring(io_uring).build()).expect("engine");
+        group.bench_function(io_name, |b| {
+            b.iter(|| engine.compress_file(&input, &output, options.clone()).expect("compress"));
+        });
+    }
+    group.finish();
+}
+
+#[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+fn bench_io_uring(_: &mut Criterion) {}
+
+#[cfg(feature = "tabular")]
+fn bench_tabular(c: &mut Criterion) {
+    use encs::{CompressionOptions, TabularMode};
+    
+    let engine = CompressionEngine::new().expect("engine");
+    let dir = tempfile::TempDir::new().expect("temp dir");
+    let input = dir.path().join("table.csv");
+    let output = dir.path().join("table.encs");
+    let table = csv_table();
+    std::fs::write(&input, &table).expect("write input");
+    
+    let modes = [("rows", None), ("columns", Some(TabularMode::Csv { delimiter: b',' }))];
+    let mut group = c.benchmark_group("compress_file/csv");
+    group.throughput(Throughput::Bytes(table.len() as u64));
+    for (mode_name, mode) in modes {
+        let mut builder = CompressionOptions::builder().algorithm(CompressionAlgorithm::Zstd { level: 3 });
+        if let Some(mode) = mode {
+            builder = builder.tabular(mode);
+        }
+        let options = builder.build();
+        
+        let metadata = engine.compress_file(&input, &output, options.clone()).expect("compress");
+        println!(
+            "csv/{}: {} -> {} bytes (ratio {:.3})",
+            mode_name,
+            metadata.metrics.original_size,
+            metadata.metrics.compressed_size,
+            metadata.metrics.compressed_size as f64 / metadata.metrics.original_size as f64
+        );
+        
+        group.bench_function(mode_name, |b| {
+            b.iter(|| engine.compress_file(&input, &output, options.clone()).expect("compress"));
+        });
+    }
+    group.finish();
+}
+
+#[cfg(not(feature = "tabular"))]
+fn bench_tabular(_: &mut Criterion) {}
+
+fn bench_pre_filter(c: &mut Criterion) {
+    use encs::{CompressionOptions, PreFilter};
+    
+    let engine = CompressionEngine::new().expect("engine");
+    let dir = tempfile::TempDir::new().expect("temp dir");
+    let input = dir.path().join("series.f32");
+    let output = dir.path().join("series.encs");
+    let series = float_series();
+    std::fs::write(&input, &series).expect("write input");
+    
+    let mut group = c.benchmark_group("compress_file/f32_series");
+    group.throughput(Throughput::Bytes(series.len() as u64));
+    for filter in [PreFilter::None, PreFilter::DeltaF32Le, PreFilter::Bitshuffle] {
+        let options = CompressionOptions::builder()
+            .algorithm(CompressionAlgorithm::Zstd { level: 3 })
+            .pre_filter(filter)
+            .build();
+        let filter_name = format!("{:?}", filter);
+        
+        let metadata = engine.compress_file(&input, &output, options.clone()).expect("compress");
+        println!(
+            "f32_series/{}: {} -> {} bytes (ratio {:.3})",
+            filter_name,
+            metadata.metrics.original_size,
+            metadata.metrics.compressed_size,
+            metadata.metrics.compressed_size as f64 / metadata.metrics.original_size as f64
+        );
+        
+        group.bench_function(filter_name, |b| {
+            b.iter(|| engine.compress_file(&input, &output, options.clone()).expect("compress"));
+        });
+    }
+    group.finish();
+}
+
+fn config() -> Criterion {
+    // Plain output so CI logs can be parsed
+    Criterion::default().with_output_color(false)
+}
+
+criterion_group! {
+    name = benches;
+    config = config();
+    targets = bench_codecs, bench_zstd_long_range, bench_parallel_chunks, bench_io_uring, bench_tabular, bench_pre_filter
+}
+criterion_main!(benches);